@@ -0,0 +1,311 @@
+//! An async facade over [`crate::FileDB`], gated behind the `tokio` feature.
+//!
+//! [`AsyncFileDB`] wraps a `tokio_rusqlite::Connection`, which owns a blocking
+//! `rusqlite::Connection` on a dedicated background thread. Every method here hands its work to
+//! that thread via `Connection::call` and awaits the result, so storing or fetching a large blob
+//! never blocks the calling runtime's executor.
+
+use rusqlite::{blob::ZeroBlob, DatabaseName, OptionalExtension, ToSql};
+
+/// An async handle to a file database, backed by the same on-disk schema as [`crate::FileDB`].
+pub struct AsyncFileDB {
+    conn: tokio_rusqlite::Connection,
+}
+
+impl AsyncFileDB {
+    /// Connect to a file database stored at the provided path, migrating its schema if needed.
+    ///
+    /// See [`FileDB::connect`](crate::FileDB::connect).
+    pub async fn connect<P>(path: P) -> Result<Self, crate::error::Error>
+    where
+        P: AsRef<std::path::Path> + Send + 'static,
+    {
+        let path = path.as_ref().to_owned();
+        let conn = tokio_rusqlite::Connection::open(path)
+            .await
+            .map_err(crate::error::Error::from)?;
+
+        conn.call(|conn| crate::migrations::migrate(conn).map_err(wrap))
+            .await
+            .map_err(crate::error::Error::from)?;
+
+        Ok(Self { conn })
+    }
+
+    /// Add a file to the database. See [`FileDB::add_file`](crate::FileDB::add_file).
+    pub async fn add_file(
+        &self,
+        key: String,
+        time_stamp: chrono::NaiveDateTime,
+        data: Vec<u8>,
+    ) -> Result<(), crate::error::Error> {
+        let time_stamp: i64 = time_stamp.timestamp();
+
+        self.conn
+            .call(move |conn| {
+                let (compressed_data, checksum) =
+                    crate::filedb::compress(&data[..]).map_err(wrap)?;
+
+                conn.execute(
+                    crate::filedb::DB_INSERT_FILE_QUERY,
+                    &[
+                        &key as &dyn ToSql,
+                        &time_stamp as &dyn ToSql,
+                        &ZeroBlob(compressed_data.len() as i32) as &dyn ToSql,
+                        &checksum.as_slice() as &dyn ToSql,
+                    ],
+                )?;
+                let row_id = conn.last_insert_rowid();
+
+                let mut blob =
+                    conn.blob_open(DatabaseName::Main, "files", "data", row_id, false)?;
+                std::io::Write::write_all(&mut blob, &compressed_data)?;
+
+                Ok::<_, rusqlite::Error>(())
+            })
+            .await
+            .map_err(crate::error::Error::from)
+    }
+
+    /// Retrieve a file from the database. See [`FileDB::retrieve_file`](crate::FileDB::retrieve_file).
+    pub async fn retrieve_file(
+        &self,
+        key: String,
+        time_stamp: chrono::NaiveDateTime,
+    ) -> Result<Option<Vec<u8>>, crate::error::Error> {
+        let time_stamp: i64 = time_stamp.timestamp();
+
+        self.conn
+            .call(move |conn| retrieve_one(conn, &key, time_stamp))
+            .await
+            .map_err(crate::error::Error::from)
+    }
+
+    /// List all files in the database. See [`FileDB::list_all`](crate::FileDB::list_all).
+    pub async fn list_all(
+        &self,
+    ) -> Result<Vec<(String, chrono::NaiveDateTime)>, crate::error::Error> {
+        self.conn
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT key, time_stamp FROM files")?;
+                let all = stmt
+                    .query_map(rusqlite::NO_PARAMS, |row| {
+                        row.get(0).and_then(|key| row.get(1).map(|ts| (key, ts)))
+                    })?
+                    .filter_map(|res: rusqlite::Result<(String, i64)>| res.ok())
+                    .map(|(key, ts)| (key, chrono::NaiveDateTime::from_timestamp(ts, 0)))
+                    .collect();
+
+                Ok::<_, rusqlite::Error>(all)
+            })
+            .await
+            .map_err(crate::error::Error::from)
+    }
+
+    /// Retrieve the newest version of `key` no newer than `at`.
+    /// See [`FileDB::retrieve_latest`](crate::FileDB::retrieve_latest).
+    pub async fn retrieve_latest(
+        &self,
+        key: String,
+        at: chrono::NaiveDateTime,
+    ) -> Result<Option<Vec<u8>>, crate::error::Error> {
+        let at: i64 = at.timestamp();
+
+        self.conn
+            .call(move |conn| {
+                let time_stamp: Option<i64> = conn
+                    .query_row(
+                        crate::filedb::DB_RETRIEVE_LATEST_TIMESTAMP_QUERY,
+                        &[&key as &dyn ToSql, &at as &dyn ToSql],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+
+                let time_stamp = match time_stamp {
+                    Some(time_stamp) => time_stamp,
+                    None => return Ok(None),
+                };
+
+                retrieve_one(conn, &key, time_stamp)
+            })
+            .await
+            .map_err(crate::error::Error::from)
+    }
+
+    /// List every time stamp stored for `key`, newest first.
+    /// See [`FileDB::list_versions`](crate::FileDB::list_versions).
+    pub async fn list_versions(
+        &self,
+        key: String,
+    ) -> Result<Vec<chrono::NaiveDateTime>, crate::error::Error> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(crate::filedb::DB_LIST_VERSIONS_QUERY)?;
+                let versions = stmt
+                    .query_map(&[&key as &dyn ToSql], |row| row.get::<_, i64>(0))?
+                    .filter_map(|res| res.ok())
+                    .map(|ts| chrono::NaiveDateTime::from_timestamp(ts, 0))
+                    .collect();
+
+                Ok::<_, rusqlite::Error>(versions)
+            })
+            .await
+            .map_err(crate::error::Error::from)
+    }
+
+    /// Retrieve every version of `key` with a time stamp between `start` and `end`, inclusive.
+    /// See [`FileDB::retrieve_range`](crate::FileDB::retrieve_range).
+    pub async fn retrieve_range(
+        &self,
+        key: String,
+        start: chrono::NaiveDateTime,
+        end: chrono::NaiveDateTime,
+    ) -> Result<Vec<(chrono::NaiveDateTime, Vec<u8>)>, crate::error::Error> {
+        let start: i64 = start.timestamp();
+        let end: i64 = end.timestamp();
+
+        self.conn
+            .call(move |conn| {
+                let time_stamps: Vec<i64> = {
+                    let mut stmt =
+                        conn.prepare(crate::filedb::DB_RETRIEVE_RANGE_TIMESTAMPS_QUERY)?;
+                    let time_stamps = stmt
+                        .query_map(
+                            &[&key as &dyn ToSql, &start as &dyn ToSql, &end as &dyn ToSql],
+                            |row| row.get(0),
+                        )?
+                        .filter_map(|res| res.ok())
+                        .collect();
+                    time_stamps
+                };
+
+                let mut result = Vec::with_capacity(time_stamps.len());
+                for time_stamp in time_stamps {
+                    if let Some(data) = retrieve_one(conn, &key, time_stamp)? {
+                        result.push((chrono::NaiveDateTime::from_timestamp(time_stamp, 0), data));
+                    }
+                }
+
+                Ok::<_, rusqlite::Error>(result)
+            })
+            .await
+            .map_err(crate::error::Error::from)
+    }
+}
+
+/// Look up the row for `key`/`time_stamp` and decompress its data, whether stored as a single
+/// blob or as a chunk manifest.
+fn retrieve_one(
+    conn: &rusqlite::Connection,
+    key: &str,
+    time_stamp: i64,
+) -> rusqlite::Result<Option<Vec<u8>>> {
+    let row: Option<(i64, Option<Vec<u8>>)> = conn
+        .query_row(
+            crate::filedb::DB_RETRIEVE_ROW_QUERY,
+            &[&key as &dyn ToSql, &time_stamp as &dyn ToSql],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    let (row_id, manifest) = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    let mut buf = Vec::new();
+    let result = match manifest {
+        Some(manifest) => crate::filedb::retrieve_chunks(conn, &manifest, &mut buf),
+        None => crate::filedb::retrieve_blob(conn, row_id, &mut buf),
+    };
+    result.map_err(wrap)?;
+
+    Ok(Some(buf))
+}
+
+/// Box a [`crate::error::Error`] as the `Send + Sync` trait object `rusqlite::Error` requires,
+/// for smuggling it out of a `tokio_rusqlite::Connection::call` closure, which must return a
+/// plain `rusqlite::Result<T>`.
+fn wrap(err: crate::error::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::AsyncFileDB;
+
+    #[tokio::test]
+    async fn test_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = AsyncFileDB::connect(db_fname.to_owned()).await.unwrap();
+
+        let time_stamp = chrono::offset::Utc::now().naive_utc();
+        db.add_file(
+            "key".to_string(),
+            time_stamp,
+            b"some file contents".to_vec(),
+        )
+        .await
+        .unwrap();
+
+        let bytes = db
+            .retrieve_file("key".to_string(), time_stamp)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bytes, b"some file contents");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_file() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = AsyncFileDB::connect(db_fname.to_owned()).await.unwrap();
+
+        let time_stamp = chrono::offset::Utc::now().naive_utc();
+        let bytes = db
+            .retrieve_file("missing".to_string(), time_stamp)
+            .await
+            .unwrap();
+        assert!(bytes.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_latest_and_range() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = AsyncFileDB::connect(db_fname.to_owned()).await.unwrap();
+
+        let t1 = chrono::NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let t2 = t1 + chrono::Duration::seconds(1);
+        db.add_file("key".to_string(), t1, b"first".to_vec())
+            .await
+            .unwrap();
+        db.add_file("key".to_string(), t2, b"second".to_vec())
+            .await
+            .unwrap();
+
+        let latest = db
+            .retrieve_latest("key".to_string(), t2)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest, b"second");
+
+        let versions = db.list_versions("key".to_string()).await.unwrap();
+        assert_eq!(versions, vec![t2, t1]);
+
+        let range = db.retrieve_range("key".to_string(), t1, t2).await.unwrap();
+        assert_eq!(
+            range,
+            vec![(t1, b"first".to_vec()), (t2, b"second".to_vec())]
+        );
+
+        Ok(())
+    }
+}
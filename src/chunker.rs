@@ -0,0 +1,180 @@
+//! Content-defined chunking, used by the deduplicating storage mode in [`crate::filedb`].
+//!
+//! Chunk boundaries are placed using a rolling buzhash over a sliding window: a boundary is
+//! declared once the low [`BOUNDARY_BITS`] bits of the hash are all zero. Because the boundary
+//! only depends on the bytes in the current window, inserting or deleting bytes in the middle of
+//! a file shifts the boundaries touching the edit but leaves the rest of the chunks unchanged, so
+//! re-archiving a lightly modified file reuses most of its previous chunks.
+
+/// Width, in bytes, of the rolling hash window.
+const WINDOW_SIZE: usize = 48;
+
+/// Tunable chunk-size bounds for [`chunk`].
+///
+/// The chunker forces a boundary at `max`, never emits a boundary before `min` (except for the
+/// final chunk of a file), and otherwise aims for `avg` by declaring a boundary wherever the low
+/// bits of the rolling hash are all zero. Because that test can only target boundary densities of
+/// `1/2^n`, `avg` must be a power of two; see [`validate`](Self::validate).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkerConfig {
+    /// Smallest chunk the chunker will emit, except for the final chunk of a file.
+    pub min: usize,
+    /// The chunker aims for this average chunk size on well-mixed input. Must be a power of two.
+    pub avg: usize,
+    /// Largest chunk the chunker will ever emit; a boundary is forced here even if the rolling
+    /// hash never hits the target pattern.
+    pub max: usize,
+}
+
+impl Default for ChunkerConfig {
+    fn default() -> Self {
+        ChunkerConfig {
+            min: 2 * 1024,
+            avg: 64 * 1024,
+            max: 256 * 1024,
+        }
+    }
+}
+
+impl ChunkerConfig {
+    /// Check that `avg` is a power of two, as the rolling hash boundary test requires.
+    pub(crate) fn validate(&self) -> Result<(), crate::error::Error> {
+        if !self.avg.is_power_of_two() {
+            return Err(crate::error::Error::general_error(format!(
+                "ChunkerConfig::avg must be a power of two, got {}",
+                self.avg
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Number of low bits of the rolling hash that must be zero to declare a boundary. Chosen so
+    /// the expected run length before a match is `avg`.
+    fn boundary_bits(&self) -> u32 {
+        self.avg.trailing_zeros()
+    }
+}
+
+/// Split `data` into content-defined chunks, using `config` for the min/avg/max chunk-size
+/// bounds.
+///
+/// Returns an empty vector for empty input, otherwise every byte of `data` is covered by exactly
+/// one chunk, in order.
+pub(crate) fn chunk<'a>(data: &'a [u8], config: &ChunkerConfig) -> Vec<&'a [u8]> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let boundary_bits = config.boundary_bits();
+    let table = hash_table();
+    let mut chunks = Vec::new();
+    let mut start = 0usize;
+    let mut hash: u64 = 0;
+
+    for i in 0..data.len() {
+        hash = hash.rotate_left(1) ^ table[data[i] as usize];
+
+        let len = i - start + 1;
+        if len > WINDOW_SIZE {
+            let outgoing = data[i - WINDOW_SIZE] as usize;
+            hash ^= table[outgoing].rotate_left((WINDOW_SIZE % 64) as u32);
+        }
+
+        let at_target = len >= config.min && hash & ((1u64 << boundary_bits) - 1) == 0;
+        let at_max = len >= config.max;
+
+        if at_target || at_max {
+            chunks.push(&data[start..=i]);
+            start = i + 1;
+            hash = 0;
+        }
+    }
+
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+
+    chunks
+}
+
+/// A fixed pseudo-random table mapping each byte value to a 64-bit value for the buzhash above.
+/// The values are arbitrary; all that matters is that they are well mixed and stable across runs
+/// so the same input always chunks the same way.
+fn hash_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut seed: u64 = 0x9E3779B97F4A7C15;
+
+    for slot in table.iter_mut() {
+        seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        *slot = z ^ (z >> 31);
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_covers_all_bytes_in_order() {
+        let config = ChunkerConfig::default();
+        let data: Vec<u8> = (0..3 * config.max).map(|i| (i % 251) as u8).collect();
+
+        let chunks = chunk(&data, &config);
+        let reassembled: Vec<u8> = chunks.iter().flat_map(|c| c.iter().copied()).collect();
+
+        assert_eq!(data, reassembled);
+        assert!(chunks.iter().all(|c| c.len() <= config.max));
+    }
+
+    #[test]
+    fn test_empty_input_has_no_chunks() {
+        assert!(chunk(&[], &ChunkerConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn test_shared_prefix_reuses_leading_chunks() {
+        let config = ChunkerConfig::default();
+        let base: Vec<u8> = (0..4 * config.avg).map(|i| (i % 241) as u8).collect();
+        let mut edited = base.clone();
+        edited.push(0xFF);
+        edited.push(0xEE);
+
+        let base_chunks = chunk(&base, &config);
+        let edited_chunks = chunk(&edited, &config);
+
+        assert_eq!(base_chunks[0], edited_chunks[0]);
+    }
+
+    #[test]
+    fn test_custom_bounds_are_honored() {
+        let config = ChunkerConfig {
+            min: 64,
+            avg: 256,
+            max: 1024,
+        };
+        let data: Vec<u8> = (0..8 * config.max).map(|i| (i % 241) as u8).collect();
+
+        let chunks = chunk(&data, &config);
+
+        assert!(chunks.iter().all(|c| c.len() <= config.max));
+        assert!(chunks.len() > 1);
+    }
+
+    #[test]
+    fn test_validate_rejects_non_power_of_two_avg() {
+        let config = ChunkerConfig {
+            min: 64,
+            avg: 1000,
+            max: 1024,
+        };
+
+        assert!(config.validate().is_err());
+        assert!(ChunkerConfig::default().validate().is_ok());
+    }
+}
@@ -7,11 +7,20 @@ pub enum Error {
     /// A general error originating in this crate with a message describing it.
     GeneralError(String),
     /// Any error from another library such as std or rusqlite is passed up this way.
-    InternalError(Box<dyn std::error::Error>),
+    InternalError(Box<dyn std::error::Error + Send + Sync>),
     /// No data for that time stamp is available, the key and time stamp are returned in the error.
     TimeStampNotAvailable(String, chrono::NaiveDateTime),
     /// There was no match for the requested key, the internal value is the requested key.
     NoMatch(String),
+    /// A schema migration failed to apply. The target schema version and the underlying error
+    /// are returned.
+    Migration(i64, Box<dyn std::error::Error + Send + Sync>),
+    /// The stored checksum for an entry did not match the checksum of its decompressed bytes,
+    /// the key and time stamp are returned in the error.
+    ChecksumMismatch(String, chrono::NaiveDateTime),
+    /// The stored data for an entry could not be decompressed, the key and time stamp are
+    /// returned in the error.
+    CorruptData(String, chrono::NaiveDateTime),
 }
 
 impl Error {
@@ -36,6 +45,23 @@ impl std::fmt::Display for Error {
                     key, time_stamp
                 )
             }
+            Self::Migration(version, err) => {
+                write!(f, "migration to schema version {} failed: {}", version, err)
+            }
+            Self::ChecksumMismatch(key, time_stamp) => {
+                write!(
+                    f,
+                    "Checksum mismatch for key {} and time stamp {}",
+                    key, time_stamp
+                )
+            }
+            Self::CorruptData(key, time_stamp) => {
+                write!(
+                    f,
+                    "Stored data for key {} and time stamp {} could not be decompressed",
+                    key, time_stamp
+                )
+            }
         }
     }
 }
@@ -44,6 +70,7 @@ impl std::error::Error for Error {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         match self {
             Self::InternalError(err) => Some(err.as_ref()),
+            Self::Migration(_, err) => Some(err.as_ref()),
             _ => None,
         }
     }
@@ -51,6 +78,7 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::InternalError(err) => Some(err.as_ref()),
+            Self::Migration(_, err) => Some(err.as_ref()),
             _ => None,
         }
     }
@@ -1,22 +1,93 @@
-use rusqlite::{OptionalExtension, ToSql};
-use std::io::Write;
+use crate::chunker::ChunkerConfig;
+use crate::retention::RetentionPolicy;
+use rusqlite::{blob::ZeroBlob, DatabaseName, OptionalExtension, ToSql};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+/// The size, in bytes, of the buffer used to stream data into and out of a stored blob.
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The length, in bytes, of a chunk id (a SHA-256 digest).
+pub(crate) const CHUNK_ID_LEN: usize = 32;
 
 /// A handle to a file database on the local file system.
 ///
 /// Currently, this type refers to an SQLITE3 database underneath. The binary text data from the
-/// file is stored in the database rows in a compressed format. This is probably NOT a good way to
-/// store large files.
+/// file is stored in the database rows in a compressed format.
 pub struct FileDB {
     conn: rusqlite::Connection,
+    retention_policy: RetentionPolicy,
+    chunker_config: ChunkerConfig,
 }
 
 impl FileDB {
     /// Connect to a file database stored at the provided path.
+    ///
+    /// If the database was created by an older version of this crate, its schema is
+    /// transparently upgraded in place by applying any outstanding migrations. The retention
+    /// policy starts out as [`RetentionPolicy::KeepAll`]; nothing is ever deleted unless
+    /// [`set_retention_policy`](Self::set_retention_policy) and [`prune`](Self::prune) are used.
+    /// The chunker config used by [`add_file_deduped`](Self::add_file_deduped) starts out as
+    /// [`ChunkerConfig::default`].
     pub fn connect<P: AsRef<std::path::Path>>(path: P) -> Result<Self, crate::error::Error> {
-        let conn = rusqlite::Connection::open(path.as_ref())?;
-        conn.execute(DB_INIT_QUERY, rusqlite::NO_PARAMS)?;
+        let mut conn = rusqlite::Connection::open(path.as_ref())?;
+        crate::migrations::migrate(&mut conn)?;
+
+        Ok(Self {
+            conn,
+            retention_policy: RetentionPolicy::default(),
+            chunker_config: ChunkerConfig::default(),
+        })
+    }
 
-        Ok(Self { conn })
+    /// Set the retention policy used by [`prune`](Self::prune).
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        self.retention_policy = policy;
+    }
+
+    /// Set the chunk-size bounds used by [`add_file_deduped`](Self::add_file_deduped).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, leaving the current config untouched, if `config.avg` is not a power of
+    /// two (see [`ChunkerConfig`]).
+    pub fn set_chunker_config(&mut self, config: ChunkerConfig) -> Result<(), crate::error::Error> {
+        config.validate()?;
+        self.chunker_config = config;
+
+        Ok(())
+    }
+
+    /// Delete entries that fall outside the current [`RetentionPolicy`].
+    ///
+    /// This is never called automatically; the caller decides when to prune. With the default
+    /// policy, [`RetentionPolicy::KeepAll`], this is a no-op.
+    ///
+    /// # Returns
+    ///
+    /// The number of rows removed.
+    pub fn prune(&self) -> Result<usize, crate::error::Error> {
+        match &self.retention_policy {
+            RetentionPolicy::KeepAll => Ok(0),
+            RetentionPolicy::KeepLastN(n) => {
+                let n = *n as i64;
+                Ok(self
+                    .conn
+                    .execute(DB_PRUNE_KEEP_LAST_N_QUERY, &[&n as &dyn ToSql])?)
+            }
+            RetentionPolicy::KeepWithin(duration) => {
+                let cutoff = (chrono::offset::Utc::now().naive_utc() - *duration).timestamp();
+                Ok(self
+                    .conn
+                    .execute(DB_PRUNE_KEEP_WITHIN_QUERY, &[&cutoff as &dyn ToSql])?)
+            }
+        }
+    }
+
+    /// The current schema version of the underlying database, as tracked by `PRAGMA user_version`.
+    pub fn schema_version(&self) -> Result<i64, crate::error::Error> {
+        crate::migrations::schema_version(&self.conn)
     }
 
     /// Retrieve a file from the database.
@@ -31,54 +102,272 @@ impl FileDB {
     ///
     /// # Returns
     ///
-    /// It returns the content of the file in a buffer. If the database entry was `NULL`, then it
-    /// returns `None` in the option. If it can't find a file with the correct key and time stamp
-    /// then it will return an error.
+    /// It returns the content of the file in a buffer. If it can't find a file with the correct
+    /// key and time stamp then it returns `None`.
+    ///
+    /// This buffers the whole file in memory; for large files prefer
+    /// [`retrieve_file_to_writer`](Self::retrieve_file_to_writer).
     pub fn retrieve_file(
         &self,
         key: &str,
         time_stamp: chrono::NaiveDateTime,
     ) -> Result<Option<Vec<u8>>, crate::error::Error> {
-        let mut writer = Vec::new();
-        let mut deflater = flate2::write::ZlibDecoder::new(writer);
+        let mut buf = Vec::new();
 
+        if self.retrieve_file_to_writer(key, time_stamp, &mut buf)? {
+            Ok(Some(buf))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Retrieve a file from the database, streaming it into `writer` as it is decompressed.
+    ///
+    /// # Arguments
+    ///
+    /// * key - Same as [`retrieve_file`](Self::retrieve_file).
+    /// * time_stamp - Same as [`retrieve_file`](Self::retrieve_file).
+    /// * writer - Destination for the decompressed file bytes.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a file was found and written to `writer`, `false` if there is no entry for that
+    /// key and time stamp. Peak memory use is bounded by a fixed-size buffer regardless of the
+    /// size of the stored file.
+    pub fn retrieve_file_to_writer<W: Write>(
+        &self,
+        key: &str,
+        time_stamp: chrono::NaiveDateTime,
+        mut writer: W,
+    ) -> Result<bool, crate::error::Error> {
         let time_stamp: i64 = time_stamp.timestamp();
 
-        let bytes: Option<Vec<u8>> = self
+        let row: Option<(i64, Option<Vec<u8>>)> = self
             .conn
             .query_row(
-                DB_RETRIEVE_FILE_QUERY,
+                DB_RETRIEVE_ROW_QUERY,
                 &[&key as &dyn ToSql, &time_stamp as &dyn ToSql],
-                |row| row.get(0),
+                |row| Ok((row.get(0)?, row.get(1)?)),
             )
             .optional()?;
 
-        if let Some(bytes) = bytes {
-            deflater.write_all(&bytes[..])?;
-            writer = deflater.finish()?;
-            Ok(Some(writer))
-        } else {
-            Ok(None)
+        let (row_id, manifest) = match row {
+            Some(row) => row,
+            None => return Ok(false),
+        };
+
+        match manifest {
+            Some(manifest) => retrieve_chunks(&self.conn, &manifest, &mut writer)?,
+            None => retrieve_blob(&self.conn, row_id, &mut writer)?,
+        }
+
+        Ok(true)
+    }
+
+    /// Retrieve a file from the database, verifying it against its stored checksum before
+    /// returning it.
+    ///
+    /// # Arguments
+    ///
+    /// * key - Same as [`retrieve_file`](Self::retrieve_file).
+    /// * time_stamp - Same as [`retrieve_file`](Self::retrieve_file).
+    ///
+    /// # Returns
+    ///
+    /// Same as [`retrieve_file`](Self::retrieve_file), except `Error::ChecksumMismatch` is
+    /// returned instead of the file's bytes if the decompressed data doesn't match the checksum
+    /// recorded when it was stored, and `Error::CorruptData` is returned if the stored data fails
+    /// to decompress. Entries written before checksums existed have no recorded checksum and are
+    /// returned without verification.
+    pub fn retrieve_file_checked(
+        &self,
+        key: &str,
+        time_stamp: chrono::NaiveDateTime,
+    ) -> Result<Option<Vec<u8>>, crate::error::Error> {
+        let data = match self.retrieve_file(key, time_stamp) {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(None),
+            Err(crate::error::Error::InternalError(err)) if is_decompress_failure(err.as_ref()) => {
+                return Err(crate::error::Error::CorruptData(
+                    key.to_string(),
+                    time_stamp,
+                ));
+            }
+            Err(err) => return Err(err),
+        };
+
+        if let Some(checksum) = self.checksum_for(key, time_stamp)? {
+            if Sha256::digest(&data).as_slice() != checksum.as_slice() {
+                return Err(crate::error::Error::ChecksumMismatch(
+                    key.to_string(),
+                    time_stamp,
+                ));
+            }
+        }
+
+        Ok(Some(data))
+    }
+
+    /// Verify that the stored entry for `key`/`time_stamp` decompresses and matches its recorded
+    /// checksum.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the stored data decompresses and either has no recorded checksum or matches it.
+    /// `false` if the checksum doesn't match or the stored data fails to decompress.
+    pub fn verify_file(
+        &self,
+        key: &str,
+        time_stamp: chrono::NaiveDateTime,
+    ) -> Result<bool, crate::error::Error> {
+        match self.retrieve_file_checked(key, time_stamp) {
+            Ok(Some(_)) => Ok(true),
+            Ok(None) => Err(crate::error::Error::NoMatch(key.to_string())),
+            Err(crate::error::Error::ChecksumMismatch(_, _)) => Ok(false),
+            Err(crate::error::Error::CorruptData(_, _)) => Ok(false),
+            Err(err) => Err(err),
         }
     }
 
+    /// Verify every entry in the archive, the same way [`verify_file`](Self::verify_file) does.
+    ///
+    /// # Returns
+    ///
+    /// The key and time stamp of every entry that failed verification.
+    pub fn verify_all(&self) -> Result<Vec<(String, chrono::NaiveDateTime)>, crate::error::Error> {
+        let mut failures = Vec::new();
+
+        for (key, time_stamp) in self.list_all()? {
+            if !self.verify_file(&key, time_stamp)? {
+                failures.push((key, time_stamp));
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Fetch the recorded checksum for `key`/`time_stamp`, if there is a row and it has one.
+    fn checksum_for(
+        &self,
+        key: &str,
+        time_stamp: chrono::NaiveDateTime,
+    ) -> Result<Option<Vec<u8>>, crate::error::Error> {
+        let time_stamp: i64 = time_stamp.timestamp();
+
+        self.conn
+            .query_row(
+                DB_RETRIEVE_CHECKSUM_QUERY,
+                &[&key as &dyn ToSql, &time_stamp as &dyn ToSql],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::flatten)
+            .map_err(Into::into)
+    }
+
     /// List all files in the database.
     ///
     /// # Returns
-    /// 
-    /// Returns an interator of tuples with the key and timestamp of all the files in the 
+    ///
+    /// Returns a vector of tuples with the key and timestamp of all the files in the
     /// archive.
     pub fn list_all(&'_ self) -> Result<Vec<(String, chrono::NaiveDateTime)>, crate::error::Error> {
-
         let mut stmt = self.conn.prepare("SELECT key, time_stamp FROM files")?;
 
-        let all = stmt.
-            query_map(rusqlite::NO_PARAMS, |row| row.get(0).and_then(|key| row.get(1).map(|ts| (key, ts))))?
+        let all = stmt
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                row.get(0).and_then(|key| row.get(1).map(|ts| (key, ts)))
+            })?
             .filter_map(|res| res.ok())
-            .map(|(key, ts)| (key, chrono::NaiveDateTime::from_timestamp(ts, 0))).collect();
+            .map(|(key, ts)| (key, chrono::NaiveDateTime::from_timestamp(ts, 0)))
+            .collect();
 
         Ok(all)
+    }
+
+    /// Retrieve the newest version of `key` that is no newer than `at`.
+    ///
+    /// This is the "as-of" lookup: the one you want when you know roughly when a file was
+    /// archived but not its exact time stamp.
+    ///
+    /// # Returns
+    ///
+    /// The file's bytes, or `None` if `key` has no version with a time stamp `<= at`.
+    pub fn retrieve_latest(
+        &self,
+        key: &str,
+        at: chrono::NaiveDateTime,
+    ) -> Result<Option<Vec<u8>>, crate::error::Error> {
+        let at: i64 = at.timestamp();
+
+        let time_stamp: Option<i64> = self
+            .conn
+            .query_row(
+                DB_RETRIEVE_LATEST_TIMESTAMP_QUERY,
+                &[&key as &dyn ToSql, &at as &dyn ToSql],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match time_stamp {
+            Some(time_stamp) => {
+                self.retrieve_file(key, chrono::NaiveDateTime::from_timestamp(time_stamp, 0))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// List every time stamp stored for `key`, newest first.
+    pub fn list_versions(
+        &self,
+        key: &str,
+    ) -> Result<Vec<chrono::NaiveDateTime>, crate::error::Error> {
+        let mut stmt = self.conn.prepare(DB_LIST_VERSIONS_QUERY)?;
+
+        let versions = stmt
+            .query_map(&[&key as &dyn ToSql], |row| row.get(0))?
+            .filter_map(|res: Result<i64, rusqlite::Error>| res.ok())
+            .map(|ts| chrono::NaiveDateTime::from_timestamp(ts, 0))
+            .collect();
+
+        Ok(versions)
+    }
+
+    /// Retrieve every version of `key` with a time stamp between `start` and `end`, inclusive.
+    ///
+    /// # Returns
+    ///
+    /// The matching `(time_stamp, data)` pairs, ordered oldest to newest.
+    pub fn retrieve_range(
+        &self,
+        key: &str,
+        start: chrono::NaiveDateTime,
+        end: chrono::NaiveDateTime,
+    ) -> Result<Vec<(chrono::NaiveDateTime, Vec<u8>)>, crate::error::Error> {
+        let start: i64 = start.timestamp();
+        let end: i64 = end.timestamp();
+
+        let time_stamps: Vec<i64> = {
+            let mut stmt = self.conn.prepare(DB_RETRIEVE_RANGE_TIMESTAMPS_QUERY)?;
+            let time_stamps = stmt
+                .query_map(
+                    &[&key as &dyn ToSql, &start as &dyn ToSql, &end as &dyn ToSql],
+                    |row| row.get(0),
+                )?
+                .filter_map(|res| res.ok())
+                .collect();
+            time_stamps
+        };
+
+        let mut result = Vec::with_capacity(time_stamps.len());
+        for time_stamp in time_stamps {
+            let time_stamp = chrono::NaiveDateTime::from_timestamp(time_stamp, 0);
+            if let Some(data) = self.retrieve_file(key, time_stamp)? {
+                result.push((time_stamp, data));
+            }
+        }
 
+        Ok(result)
     }
 
     /// Add a file to the database.
@@ -92,17 +381,34 @@ impl FileDB {
     /// time stamps generated.
     /// * data - Is the file contents to store in the database.
     ///
+    /// This buffers the whole file in memory; for large files prefer
+    /// [`add_file_from_reader`](Self::add_file_from_reader).
     pub fn add_file(
         &self,
         key: &str,
         time_stamp: chrono::NaiveDateTime,
         data: &[u8],
     ) -> Result<(), crate::error::Error> {
-        let compressed_data: Vec<u8> = Vec::with_capacity(data.len());
-        let mut encoder =
-            flate2::write::ZlibEncoder::new(compressed_data, flate2::Compression::default());
-        encoder.write_all(data)?;
-        let compressed_data = encoder.finish()?;
+        self.add_file_from_reader(key, time_stamp, data)
+    }
+
+    /// Add a file to the database, streaming it in from `reader` as it is compressed.
+    ///
+    /// # Arguments
+    ///
+    /// * key - Same as [`add_file`](Self::add_file).
+    /// * time_stamp - Same as [`add_file`](Self::add_file).
+    /// * reader - Source of the file bytes to store.
+    ///
+    /// Peak memory use is bounded by a fixed-size buffer plus the size of the compressed output,
+    /// regardless of the size of `reader`.
+    pub fn add_file_from_reader<R: Read>(
+        &self,
+        key: &str,
+        time_stamp: chrono::NaiveDateTime,
+        reader: R,
+    ) -> Result<(), crate::error::Error> {
+        let (compressed_data, checksum) = compress(reader)?;
 
         let time_stamp: i64 = time_stamp.timestamp();
 
@@ -111,25 +417,210 @@ impl FileDB {
             &[
                 &key as &dyn ToSql,
                 &time_stamp as &dyn ToSql,
-                &compressed_data as &dyn ToSql,
+                &ZeroBlob(compressed_data.len() as i32) as &dyn ToSql,
+                &checksum.as_slice() as &dyn ToSql,
+            ],
+        )?;
+        let row_id = self.conn.last_insert_rowid();
+
+        let mut blob = self
+            .conn
+            .blob_open(DatabaseName::Main, "files", "data", row_id, false)?;
+        blob.write_all(&compressed_data)?;
+
+        Ok(())
+    }
+
+    /// Add a file to the database in deduplicating storage mode.
+    ///
+    /// `data` is split into content-defined chunks (see [`crate::chunker`]), each chunk is
+    /// compressed and stored once in a shared `chunks` table, and this entry's row holds only an
+    /// ordered manifest of chunk ids. Re-archiving a file that shares most of its bytes with a
+    /// version already in the archive (the common case this crate is for) only stores the chunks
+    /// that actually changed.
+    ///
+    /// # Arguments
+    ///
+    /// * key - Same as [`add_file`](Self::add_file).
+    /// * time_stamp - Same as [`add_file`](Self::add_file).
+    /// * data - Is the file contents to store in the database.
+    ///
+    /// Call [`gc`](Self::gc) periodically to reclaim chunks that are no longer referenced by any
+    /// manifest.
+    pub fn add_file_deduped(
+        &self,
+        key: &str,
+        time_stamp: chrono::NaiveDateTime,
+        data: &[u8],
+    ) -> Result<(), crate::error::Error> {
+        let checksum = Sha256::digest(data);
+
+        let tx = self.conn.unchecked_transaction()?;
+
+        let mut manifest = Vec::new();
+        for piece in crate::chunker::chunk(data, &self.chunker_config) {
+            let id = Sha256::digest(piece);
+            let (compressed, _) = compress(piece)?;
+
+            tx.execute(
+                DB_INSERT_CHUNK_QUERY,
+                &[&id.as_slice() as &dyn ToSql, &compressed as &dyn ToSql],
+            )?;
+
+            manifest.extend_from_slice(&id);
+        }
+
+        let time_stamp: i64 = time_stamp.timestamp();
+        tx.execute(
+            DB_INSERT_MANIFEST_QUERY,
+            &[
+                &key as &dyn ToSql,
+                &time_stamp as &dyn ToSql,
+                &manifest as &dyn ToSql,
+                &checksum.as_slice() as &dyn ToSql,
             ],
         )?;
 
+        tx.commit()?;
+
         Ok(())
     }
+
+    /// Delete every chunk in the `chunks` table that is no longer referenced by any file's
+    /// manifest.
+    ///
+    /// # Returns
+    ///
+    /// The number of chunks removed.
+    pub fn gc(&self) -> Result<usize, crate::error::Error> {
+        let mut referenced: HashSet<Vec<u8>> = HashSet::new();
+        {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT manifest FROM files WHERE manifest IS NOT NULL")?;
+            let manifests = stmt.query_map(rusqlite::NO_PARAMS, |row| row.get::<_, Vec<u8>>(0))?;
+            for manifest in manifests.filter_map(|res| res.ok()) {
+                for id in manifest.chunks_exact(CHUNK_ID_LEN) {
+                    referenced.insert(id.to_vec());
+                }
+            }
+        }
+
+        let mut all_ids: Vec<Vec<u8>> = Vec::new();
+        {
+            let mut stmt = self.conn.prepare("SELECT id FROM chunks")?;
+            let ids = stmt.query_map(rusqlite::NO_PARAMS, |row| row.get::<_, Vec<u8>>(0))?;
+            for id in ids.filter_map(|res| res.ok()) {
+                all_ids.push(id);
+            }
+        }
+
+        let mut removed = 0;
+        for id in all_ids {
+            if !referenced.contains(&id) {
+                self.conn
+                    .execute("DELETE FROM chunks WHERE id = ?1", &[&id as &dyn ToSql])?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Whether a boxed internal error originated from a failed zlib decode rather than, say, a
+/// database I/O problem. Used to tell corrupt compressed data apart from other internal errors.
+fn is_decompress_failure(err: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    err.downcast_ref::<std::io::Error>().is_some()
+}
+
+/// Decompress the blob stored in `files.data` for `row_id` into `writer`.
+pub(crate) fn retrieve_blob<W: Write>(
+    conn: &rusqlite::Connection,
+    row_id: i64,
+    writer: W,
+) -> Result<(), crate::error::Error> {
+    let blob = conn.blob_open(DatabaseName::Main, "files", "data", row_id, true)?;
+    let mut decoder = flate2::read::ZlibDecoder::new(blob);
+
+    copy(&mut decoder, writer)
 }
 
-impl Drop for FileDB {
-    fn drop(&mut self) {
-        let earliest_date = chrono::offset::Utc::now().naive_utc() - chrono::Duration::days(365);
-        let _ = self.conn.execute(DB_CLEANUP_QUERY, &[earliest_date]);
+/// Reassemble a file from its chunk `manifest` (an ordered concatenation of chunk ids),
+/// decompressing each chunk and writing it to `writer` in order.
+pub(crate) fn retrieve_chunks<W: Write>(
+    conn: &rusqlite::Connection,
+    manifest: &[u8],
+    mut writer: W,
+) -> Result<(), crate::error::Error> {
+    for id in manifest.chunks_exact(CHUNK_ID_LEN) {
+        let compressed: Vec<u8> =
+            conn.query_row(DB_RETRIEVE_CHUNK_QUERY, &[&id as &dyn ToSql], |row| {
+                row.get(0)
+            })?;
+
+        let mut decoder = flate2::read::ZlibDecoder::new(&compressed[..]);
+        copy(&mut decoder, &mut writer)?;
     }
+
+    Ok(())
 }
 
-const DB_INIT_QUERY: &'static str = include_str!("init_query.sql");
-const DB_RETRIEVE_FILE_QUERY: &'static str = include_str!("retrieve_file.sql");
-const DB_INSERT_FILE_QUERY: &'static str = include_str!("insert_query.sql");
-const DB_CLEANUP_QUERY: &'static str = include_str!("cleanup_query.sql");
+/// Zlib-compress every byte read from `reader` into an in-memory buffer, reading in fixed-size
+/// chunks so a slow or large reader never needs to be loaded all at once up front. Also returns
+/// the SHA-256 checksum of the uncompressed bytes, computed in the same pass over `reader`.
+pub(crate) fn compress<R: Read>(
+    mut reader: R,
+) -> Result<(Vec<u8>, [u8; CHUNK_ID_LEN]), crate::error::Error> {
+    let mut compressed_data = Vec::new();
+    let mut encoder =
+        flate2::write::ZlibEncoder::new(&mut compressed_data, flate2::Compression::default());
+    let mut hasher = Sha256::new();
+
+    let mut buf = [0u8; STREAM_BUFFER_SIZE];
+    loop {
+        let bytes_read = reader.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buf[..bytes_read]);
+        encoder.write_all(&buf[..bytes_read])?;
+    }
+    encoder.finish()?;
+
+    Ok((compressed_data, hasher.finalize().into()))
+}
+
+/// Copy every byte from `reader` to `writer` using a fixed-size buffer.
+pub(crate) fn copy<R: Read, W: Write>(
+    reader: &mut R,
+    mut writer: W,
+) -> Result<(), crate::error::Error> {
+    let mut buf = [0u8; STREAM_BUFFER_SIZE];
+    loop {
+        let bytes_read = reader.read(&mut buf)?;
+        if bytes_read == 0 {
+            break;
+        }
+        writer.write_all(&buf[..bytes_read])?;
+    }
+
+    Ok(())
+}
+
+pub(crate) const DB_RETRIEVE_ROW_QUERY: &'static str = include_str!("retrieve_row_query.sql");
+const DB_RETRIEVE_CHECKSUM_QUERY: &'static str = include_str!("retrieve_checksum_query.sql");
+pub(crate) const DB_RETRIEVE_LATEST_TIMESTAMP_QUERY: &'static str =
+    include_str!("retrieve_latest_timestamp_query.sql");
+pub(crate) const DB_LIST_VERSIONS_QUERY: &'static str = include_str!("list_versions_query.sql");
+pub(crate) const DB_RETRIEVE_RANGE_TIMESTAMPS_QUERY: &'static str =
+    include_str!("retrieve_range_timestamps_query.sql");
+const DB_RETRIEVE_CHUNK_QUERY: &'static str = include_str!("retrieve_chunk_query.sql");
+pub(crate) const DB_INSERT_FILE_QUERY: &'static str = include_str!("insert_query.sql");
+const DB_INSERT_CHUNK_QUERY: &'static str = include_str!("insert_chunk_query.sql");
+const DB_INSERT_MANIFEST_QUERY: &'static str = include_str!("insert_manifest_query.sql");
+const DB_PRUNE_KEEP_LAST_N_QUERY: &'static str = include_str!("prune_keep_last_n.sql");
+const DB_PRUNE_KEEP_WITHIN_QUERY: &'static str = include_str!("prune_keep_within.sql");
 
 #[cfg(test)]
 mod test {
@@ -178,4 +669,191 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_stream_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = super::FileDB::connect(db_fname).unwrap();
+
+        let time_stamp = chrono::offset::Utc::now().naive_utc();
+        let mut test_data: Vec<u8> = Vec::new();
+        let mut test_data_file = std::fs::File::open("src/filedb.rs").unwrap();
+        test_data_file.read_to_end(&mut test_data).unwrap();
+
+        db.add_file_from_reader("filedb.rs", time_stamp, &test_data[..])
+            .unwrap();
+
+        let mut retrieved = Vec::new();
+        let found = db
+            .retrieve_file_to_writer("filedb.rs", time_stamp, &mut retrieved)
+            .unwrap();
+
+        assert!(found);
+        assert_eq!(test_data, retrieved);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deduped_round_trip_and_gc() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = super::FileDB::connect(db_fname).unwrap();
+
+        let mut test_data: Vec<u8> = Vec::new();
+        let mut test_data_file = std::fs::File::open("src/filedb.rs").unwrap();
+        test_data_file.read_to_end(&mut test_data).unwrap();
+
+        let first_stamp = chrono::offset::Utc::now().naive_utc();
+        let second_stamp = first_stamp + chrono::Duration::seconds(1);
+
+        db.add_file_deduped("filedb.rs", first_stamp, &test_data)
+            .unwrap();
+        db.add_file_deduped("filedb.rs", second_stamp, &test_data)
+            .unwrap();
+
+        let retrieved = db
+            .retrieve_file("filedb.rs", second_stamp)
+            .unwrap()
+            .unwrap();
+        assert_eq!(test_data, retrieved);
+
+        // Nothing to collect while both versions are still present.
+        assert_eq!(db.gc().unwrap(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_keep_last_n() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let mut db = super::FileDB::connect(db_fname).unwrap();
+        db.set_retention_policy(super::RetentionPolicy::KeepLastN(1));
+
+        let first_stamp = chrono::offset::Utc::now().naive_utc();
+        let second_stamp = first_stamp + chrono::Duration::seconds(1);
+
+        db.add_file("key", first_stamp, b"first").unwrap();
+        db.add_file("key", second_stamp, b"second").unwrap();
+
+        let removed = db.prune().unwrap();
+        assert_eq!(removed, 1);
+
+        assert!(db.retrieve_file("key", first_stamp).unwrap().is_none());
+        assert!(db.retrieve_file("key", second_stamp).unwrap().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_keep_all_is_a_no_op() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = super::FileDB::connect(db_fname).unwrap();
+
+        let time_stamp = chrono::offset::Utc::now().naive_utc();
+        db.add_file("key", time_stamp, b"data").unwrap();
+
+        assert_eq!(db.prune().unwrap(), 0);
+        assert!(db.retrieve_file("key", time_stamp).unwrap().is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_file_detects_corruption() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = super::FileDB::connect(db_fname).unwrap();
+
+        let time_stamp = chrono::NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        db.add_file("key", time_stamp, b"some file contents")
+            .unwrap();
+
+        assert!(db.verify_file("key", time_stamp).unwrap());
+        assert!(db.verify_all().unwrap().is_empty());
+
+        // Corrupt the stored checksum directly so the recorded checksum no longer matches.
+        db.conn
+            .execute(
+                "UPDATE files SET checksum = X'0000000000000000000000000000000000000000000000000000000000000000'",
+                rusqlite::NO_PARAMS,
+            )
+            .unwrap();
+
+        assert!(!db.verify_file("key", time_stamp).unwrap());
+        assert_eq!(
+            db.verify_all().unwrap(),
+            vec![("key".to_string(), time_stamp)]
+        );
+
+        let err = db.retrieve_file_checked("key", time_stamp).unwrap_err();
+        assert!(matches!(err, crate::error::Error::ChecksumMismatch(_, _)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_file_detects_corrupt_blob() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = super::FileDB::connect(db_fname).unwrap();
+
+        let t1 = chrono::NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let t2 = t1 + chrono::Duration::seconds(1);
+        db.add_file("key", t1, b"some file contents").unwrap();
+        db.add_file("other", t2, b"untouched contents").unwrap();
+
+        // Corrupt the compressed bytes themselves, not just the checksum, so the zlib decoder
+        // fails outright when this entry is read back.
+        db.conn
+            .execute(
+                "UPDATE files SET data = X'ffff' WHERE key = 'key'",
+                rusqlite::NO_PARAMS,
+            )
+            .unwrap();
+
+        assert!(!db.verify_file("key", t1).unwrap());
+        let err = db.retrieve_file_checked("key", t1).unwrap_err();
+        assert!(matches!(err, crate::error::Error::CorruptData(_, _)));
+
+        // verify_all must keep scanning past the corrupt entry instead of aborting on it.
+        assert_eq!(db.verify_all().unwrap(), vec![("key".to_string(), t1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_series_queries() -> Result<(), Box<dyn std::error::Error>> {
+        let temp_db = tempfile::NamedTempFile::new_in(".").unwrap();
+        let db_fname = temp_db.path();
+        let db = super::FileDB::connect(db_fname).unwrap();
+
+        let t1 = chrono::NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let t2 = t1 + chrono::Duration::seconds(10);
+        let t3 = t1 + chrono::Duration::seconds(20);
+
+        db.add_file("key", t1, b"v1").unwrap();
+        db.add_file("key", t2, b"v2").unwrap();
+        db.add_file("key", t3, b"v3").unwrap();
+
+        assert_eq!(db.list_versions("key").unwrap(), vec![t3, t2, t1]);
+
+        let between_t1_and_t2 = t1 + chrono::Duration::seconds(5);
+        let latest = db
+            .retrieve_latest("key", between_t1_and_t2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest, b"v1");
+
+        let before_t1 = t1 - chrono::Duration::seconds(5);
+        assert!(db.retrieve_latest("key", before_t1).unwrap().is_none());
+
+        let range = db.retrieve_range("key", t1, t2).unwrap();
+        assert_eq!(range, vec![(t1, b"v1".to_vec()), (t2, b"v2".to_vec())]);
+
+        Ok(())
+    }
 }
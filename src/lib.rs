@@ -13,11 +13,21 @@ directories, but really any text could be used. The point is keys do not need to
 //
 // Public API
 //
+pub use crate::chunker::ChunkerConfig;
 pub use crate::error::Error;
 pub use crate::filedb::FileDB;
+pub use crate::retention::RetentionPolicy;
+
+#[cfg(feature = "tokio")]
+pub use crate::async_db::AsyncFileDB;
 
 //
 // Private Implementation Details
 //
+#[cfg(feature = "tokio")]
+mod async_db;
+mod chunker;
 mod error;
 mod filedb;
+mod migrations;
+mod retention;
@@ -0,0 +1,50 @@
+/// A single schema migration: the version it brings the database up to, and the SQL that does it.
+struct Migration {
+    version: i64,
+    sql: &'static str,
+}
+
+/// All migrations, in order. The `version` of the last entry is the current schema version of
+/// the crate. Adding a new column or table means appending a new entry here, never editing an
+/// existing one, so that databases created by older versions of the crate keep upgrading cleanly.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: include_str!("migrations/0001_init.sql"),
+    },
+    Migration {
+        version: 2,
+        sql: include_str!("migrations/0002_dedup.sql"),
+    },
+    Migration {
+        version: 3,
+        sql: include_str!("migrations/0003_checksum.sql"),
+    },
+];
+
+/// Bring `conn` up to the latest schema version, applying only the migrations newer than its
+/// current `PRAGMA user_version`. Each migration runs in its own transaction and the version
+/// pragma is only bumped once that migration's SQL has succeeded, so a failure partway through
+/// leaves the database at the last fully-applied version rather than in a half-migrated state.
+pub(crate) fn migrate(conn: &mut rusqlite::Connection) -> Result<(), crate::error::Error> {
+    let current_version = schema_version(conn)?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+        let tx = conn.transaction()?;
+
+        tx.execute_batch(migration.sql).map_err(|err| {
+            crate::error::Error::Migration(migration.version, Box::new(err))
+        })?;
+        tx.pragma_update(None, "user_version", &migration.version)?;
+
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Read the database's current schema version from `PRAGMA user_version`.
+pub(crate) fn schema_version(conn: &rusqlite::Connection) -> Result<i64, crate::error::Error> {
+    conn.query_row("PRAGMA user_version", rusqlite::NO_PARAMS, |row| row.get(0))
+        .map_err(Into::into)
+}
@@ -0,0 +1,19 @@
+/// Controls which entries [`FileDB::prune`](crate::FileDB::prune) is allowed to remove.
+///
+/// The default is [`RetentionPolicy::KeepAll`], so nothing is ever deleted unless the caller
+/// opts in to a policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Never delete anything; `prune` is a no-op.
+    KeepAll,
+    /// For each key, keep only the `n` most recent versions by time stamp and delete the rest.
+    KeepLastN(usize),
+    /// Keep only entries whose time stamp is within `duration` of now and delete the rest.
+    KeepWithin(chrono::Duration),
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy::KeepAll
+    }
+}